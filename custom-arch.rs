@@ -1,4 +1,4 @@
-use num_bigint::{BigInt, BigUint, ToBigInt};
+use num_bigint::BigUint;
 use num_traits::{Zero, One};
 use std::collections::HashMap;
 
@@ -23,7 +23,10 @@ struct CPU {
 }
 
 impl CPU {
-    fn new(bits: CpuWidth, reg_count: usize, mem_size: usize) -> Self {
+    fn new(bits: CpuWidth, reg_count: usize, mem_size: usize) -> Result<Self, String> {
+        if let CpuWidth::Custom(0) = bits {
+            return Err("Width must be at least 1 bit".into());
+        }
         let mut registers = HashMap::new();
         for i in 0..reg_count {
             registers.insert(format!("R{}", i), BigUint::zero());
@@ -32,25 +35,26 @@ impl CPU {
         for f in &["ZERO", "CARRY", "OVERFLOW", "SIGN"] {
             flags.insert(f.to_string(), false);
         }
-        CPU {
+        Ok(CPU {
             registers,
             bits,
             flags,
             memory: vec![BigUint::zero(); mem_size],
             pc: BigUint::zero(),
-        }
+        })
     }
 
     fn mask(&self) -> BigUint {
-        match self.bits {
-            CpuWidth::Bit32 => BigUint::from(1u32) << 32usize - BigUint::one(),
-            CpuWidth::Bit64 => BigUint::from(1u64) << 64usize - BigUint::one(),
-            CpuWidth::Bit128 => (BigUint::one() << 128usize) - BigUint::one(),
-            CpuWidth::Bit256 => (BigUint::one() << 256usize) - BigUint::one(),
-            CpuWidth::Bit512 => (BigUint::one() << 512usize) - BigUint::one(),
-            CpuWidth::Bit1024 => (BigUint::one() << 1024usize) - BigUint::one(),
-            CpuWidth::Custom(n) => (BigUint::one() << n as usize) - BigUint::one(),
-        }
+        let bits = match self.bits {
+            CpuWidth::Bit32 => 32,
+            CpuWidth::Bit64 => 64,
+            CpuWidth::Bit128 => 128,
+            CpuWidth::Bit256 => 256,
+            CpuWidth::Bit512 => 512,
+            CpuWidth::Bit1024 => 1024,
+            CpuWidth::Custom(n) => n as usize,
+        };
+        (BigUint::one() << bits) - BigUint::one()
     }
 
     fn to_masked(&self, value: &BigUint) -> BigUint {
@@ -65,9 +69,9 @@ impl CPU {
 
     fn add(&mut self, reg: &str, val: &BigUint) {
         let r_val = self.registers.get(reg).unwrap();
-        let sum = r_val + val;
-        self.registers.insert(reg.to_string(), self.to_masked(&sum));
+        let sum = self.to_masked(&(r_val + val));
         self.set_flag("ZERO", sum.is_zero());
+        self.registers.insert(reg.to_string(), sum);
         // Note: CARRY and OVERFLOW for BigUint would require extra logic
     }
 
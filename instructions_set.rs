@@ -3,107 +3,112 @@ use std::collections::HashMap;
 
 #[derive(Debug)]
 struct CPU {
-    registers: HashMap<String, i32>,
+    registers: HashMap<String, u32>,
     bits: u32,
 }
 
 impl CPU {
-    fn new(bits: u32) -> Self {
+    // Registers are unsigned values masked to the width, so every width from
+    // 1 to 32 bits divides, shifts and compares the same way.
+    fn new(bits: u32) -> Result<Self, String> {
+        if bits == 0 || bits > 32 {
+            return Err(format!("Unsupported width {} (expected 1..=32)", bits));
+        }
         let mut registers = HashMap::new();
         for i in 1..=8 {
             registers.insert(format!("R{}", i), 0);
         }
-        CPU { registers, bits }
+        Ok(CPU { registers, bits })
     }
 
-    fn mask(&self) -> i32 {
-        (1 << self.bits) - 1
+    fn mask(&self) -> u32 {
+        u32::MAX >> (32 - self.bits)
     }
 
-    fn to_twos_complement(&self, value: i32) -> i32 {
+    // Negative immediates arrive as their two's complement bits.
+    fn to_twos_complement(&self, value: u32) -> u32 {
         value & self.mask()
     }
 
-    fn get_value(&self, operand: &str) -> Result<i32, String> {
+    fn reg(&self, name: &str) -> Result<u32, String> {
+        self.registers.get(name).copied().ok_or(format!("Register {} not found", name))
+    }
+
+    fn get_value(&self, operand: &str) -> Result<u32, String> {
         if operand.starts_with('R') {
-            self.registers.get(operand)
-                .copied()
-                .ok_or(format!("Register {} not found", operand))
+            self.reg(operand)
         } else {
-            operand.parse::<i32>()
-                .map_err(|_| format!("Invalid immediate value: {}", operand))
+            operand.parse::<i64>()
+                .ok()
+                .filter(|val| (i32::MIN as i64..=u32::MAX as i64).contains(val))
+                .map(|val| val as u32)
+                .ok_or(format!("Invalid immediate value: {}", operand))
         }
     }
 
+    // "OP Rd, src": the destination (which must exist) and the source value.
+    fn operands<'a>(&self, parts: &[&'a str]) -> Result<(&'a str, u32), String> {
+        let reg = parts.get(1).ok_or(format!("{} needs a register", parts[0]))?.trim_end_matches(',');
+        self.reg(reg)?;
+        let val = self.get_value(parts.get(2).ok_or(format!("{} needs a source operand", parts[0]))?)?;
+        Ok((reg, val))
+    }
+
+    fn write(&mut self, reg: &str, value: u32) {
+        let value = self.to_twos_complement(value);
+        self.registers.insert(reg.to_string(), value);
+    }
+
     fn execute(&mut self, instruction: &str) -> Result<(), String> {
         let parts: Vec<&str> = instruction.split_whitespace().collect();
         if parts.is_empty() { return Err("Empty instruction".into()); }
 
         match parts[0].to_uppercase().as_str() {
             "MOV" => {
-                let reg = parts[1].trim_end_matches(',');
-                let val = self.get_value(parts[2])?;
-                self.registers.insert(reg.to_string(), self.to_twos_complement(val));
+                let (reg, val) = self.operands(&parts)?;
+                self.write(reg, val);
             }
             "ADD" => {
-                let reg = parts[1].trim_end_matches(',');
-                let val = self.get_value(parts[2])?;
-                let result = self.to_twos_complement(self.registers[reg] + val);
-                self.registers.insert(reg.to_string(), result);
+                let (reg, val) = self.operands(&parts)?;
+                self.write(reg, self.reg(reg)?.wrapping_add(val));
             }
             "SUB" => {
-                let reg = parts[1].trim_end_matches(',');
-                let val = self.get_value(parts[2])?;
-                let result = self.to_twos_complement(self.registers[reg] - val);
-                self.registers.insert(reg.to_string(), result);
+                let (reg, val) = self.operands(&parts)?;
+                self.write(reg, self.reg(reg)?.wrapping_sub(val));
             }
             "MUL" => {
-                let reg = parts[1].trim_end_matches(',');
-                let val = self.get_value(parts[2])?;
-                let result = self.to_twos_complement(self.registers[reg] * val);
-                self.registers.insert(reg.to_string(), result);
+                let (reg, val) = self.operands(&parts)?;
+                self.write(reg, self.reg(reg)?.wrapping_mul(val));
             }
             "DIV" => {
-                let reg = parts[1].trim_end_matches(',');
-                let val = self.get_value(parts[2])?;
+                let (reg, val) = self.operands(&parts)?;
+                let val = self.to_twos_complement(val);
                 if val == 0 { return Err("Division by zero".into()); }
-                let result = self.to_twos_complement(self.registers[reg] / val);
-                self.registers.insert(reg.to_string(), result);
+                self.write(reg, self.reg(reg)? / val);
             }
             "AND" => {
-                let reg = parts[1].trim_end_matches(',');
-                let val = self.get_value(parts[2])?;
-                let result = self.to_twos_complement(self.registers[reg] & val);
-                self.registers.insert(reg.to_string(), result);
+                let (reg, val) = self.operands(&parts)?;
+                self.write(reg, self.reg(reg)? & val);
             }
             "OR" => {
-                let reg = parts[1].trim_end_matches(',');
-                let val = self.get_value(parts[2])?;
-                let result = self.to_twos_complement(self.registers[reg] | val);
-                self.registers.insert(reg.to_string(), result);
+                let (reg, val) = self.operands(&parts)?;
+                self.write(reg, self.reg(reg)? | val);
             }
             "XOR" => {
-                let reg = parts[1].trim_end_matches(',');
-                let val = self.get_value(parts[2])?;
-                let result = self.to_twos_complement(self.registers[reg] ^ val);
-                self.registers.insert(reg.to_string(), result);
+                let (reg, val) = self.operands(&parts)?;
+                self.write(reg, self.reg(reg)? ^ val);
             }
             "NOT" => {
-                let reg = parts[1];
-                let result = self.to_twos_complement(!self.registers[reg]);
-                self.registers.insert(reg.to_string(), result);
+                let reg = parts.get(1).ok_or("NOT needs a register")?.trim_end_matches(',');
+                self.write(reg, !self.reg(reg)?);
             }
             "SHL" => {
-                let reg = parts[1].trim_end_matches(',');
-                let val = self.get_value(parts[2])?;
-                let result = self.to_twos_complement(self.registers[reg] << val);
-                self.registers.insert(reg.to_string(), result);
+                let (reg, val) = self.operands(&parts)?;
+                self.write(reg, self.reg(reg)?.checked_shl(val).unwrap_or(0));
             }
             "SHR" => {
-                let reg = parts[1].trim_end_matches(',');
-                let val = self.get_value(parts[2])?;
-                let result = self.to_twos_complement((self.registers[reg] as u32 >> val) as i32);
-                self.registers.insert(reg.to_string(), result);
+                let (reg, val) = self.operands(&parts)?;
+                self.write(reg, self.reg(reg)?.checked_shr(val).unwrap_or(0));
             }
             _ => return Err(format!("Unknown instruction: {}", parts[0])),
         }
@@ -121,7 +126,7 @@ impl CPU {
     }
 }
 fn main() {
-    let mut cpu = CPU::new(8);  // 8-bit CPU
+    let mut cpu = CPU::new(8).unwrap();  // 8-bit CPU
 
     cpu.execute("MOV R1, 10").unwrap();
     cpu.execute("MOV R2, 250").unwrap();
@@ -132,3 +137,37 @@ fn main() {
 
     cpu.dump();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn div_is_unsigned_at_every_width() {
+        for bits in [1, 8, 31, 32] {
+            let mut cpu = CPU::new(bits).unwrap();
+            let top = cpu.mask();
+            cpu.execute("MOV R1, -1").unwrap();
+            assert_eq!(cpu.registers["R1"], top, "-1 at width {}", bits);
+            cpu.execute("DIV R1, 1").unwrap();
+            assert_eq!(cpu.registers["R1"], top, "all ones / 1 at width {}", bits);
+            if bits > 1 {
+                cpu.execute("DIV R1, 2").unwrap();
+                assert_eq!(cpu.registers["R1"], top >> 1, "all ones / 2 at width {}", bits);
+                cpu.execute("MOV R2, 7").unwrap();
+                cpu.execute("DIV R2, -1").unwrap();
+                assert_eq!(cpu.registers["R2"], 0, "7 / all ones at width {}", bits);
+            }
+            assert!(cpu.execute("DIV R1, 0").is_err());
+        }
+    }
+
+    #[test]
+    fn malformed_instructions_are_errors() {
+        let mut cpu = CPU::new(8).unwrap();
+        for line in ["MOV", "MOV R1,", "ADD R9, 1", "NOT", "NOT R0", "SHL R1, R9", "DIV R1, 256", "MOV R1, 4294967296"] {
+            assert!(cpu.execute(line).is_err(), "{}", line);
+        }
+        assert!(!cpu.registers.contains_key("R9"));
+    }
+}
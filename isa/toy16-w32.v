@@ -0,0 +1,246 @@
+// toy16: single-cycle implementation generated from the ISA description.
+// 32-bit datapath, 16-bit instruction word, 16 registers, 2 write port(s).
+// Instruction memory is read at pc; data memory has one asynchronous read
+// and one synchronous write port. Words match no instruction raise illegal.
+module toy16_cpu (
+    input  wire clk,
+    input  wire rst,
+    output wire [31:0] imem_addr,
+    input  wire [15:0] imem_data,
+    output reg  [31:0] dmem_addr,
+    input  wire [31:0] dmem_rdata,
+    output reg  dmem_we,
+    output reg  [31:0] dmem_wdata,
+    output reg  illegal
+);
+    localparam W = 32;
+    localparam X = 66;
+    localparam RB = 4;
+
+    reg [W-1:0] pc;
+    reg [W-1:0] rf [0:(1<<RB)-1];
+    reg flag_z, flag_c, flag_n, flag_v;
+    wire [15:0] ir = imem_data;
+    assign imem_addr = pc;
+
+    function signed [X-1:0] zext_w(input [W-1:0] v);
+        zext_w = {{(X-W){1'b0}}, v};
+    endfunction
+
+    function signed [X-1:0] sext_w(input [X-1:0] v);
+        sext_w = {{(X-W){v[W-1]}}, v[W-1:0]};
+    endfunction
+
+    function signed [X-1:0] bit_x(input b);
+        bit_x = {{(X-1){1'b0}}, b};
+    endfunction
+
+    // ADD rd, rs, rt: rd = rs + rt
+    wire is_add = (ir & 16'hf000) == 16'h0000;
+    wire [RB-1:0] add_rd = ir[11:8];
+    wire [RB-1:0] add_rs = ir[7:4];
+    wire [RB-1:0] add_rt = ir[3:0];
+    wire signed [X-1:0] add_0 = (zext_w(rf[add_rs]) + zext_w(rf[add_rt]));
+    wire signed [X-1:0] add_1 = (sext_w(rf[add_rs]) + sext_w(rf[add_rt]));
+    wire signed [X-1:0] add_2 = zext_w(add_0[W-1:0]);
+
+    // SUB rd, rs, rt: rd = rs - rt
+    wire is_sub = (ir & 16'hf000) == 16'h1000;
+    wire [RB-1:0] sub_rd = ir[11:8];
+    wire [RB-1:0] sub_rs = ir[7:4];
+    wire [RB-1:0] sub_rt = ir[3:0];
+    wire signed [X-1:0] sub_0 = (zext_w(rf[sub_rs]) - zext_w(rf[sub_rt]));
+    wire signed [X-1:0] sub_1 = zext_w(sub_0[W-1:0]);
+
+    // ADDI rd, rs, imm: rd = rs + imm
+    wire is_addi = (ir & 16'hf000) == 16'h2000;
+    wire [RB-1:0] addi_rd = ir[11:8];
+    wire [RB-1:0] addi_rs = ir[7:4];
+    wire signed [X-1:0] addi_imm = $signed(ir[3:0]);
+    wire signed [X-1:0] addi_0 = (zext_w(rf[addi_rs]) + addi_imm);
+    wire signed [X-1:0] addi_1 = zext_w(addi_0[W-1:0]);
+
+    // LI rd, imm: rd = imm
+    wire is_li = (ir & 16'hf000) == 16'h3000;
+    wire [RB-1:0] li_rd = ir[11:8];
+    wire signed [X-1:0] li_imm = $signed(ir[7:0]);
+    wire signed [X-1:0] li_0 = zext_w(li_imm[W-1:0]);
+
+    // LUI rd, imm: rd = (rd & 0xff) | (imm << 8)
+    wire is_lui = (ir & 16'hf000) == 16'h4000;
+    wire [RB-1:0] lui_rd = ir[11:8];
+    wire signed [X-1:0] lui_imm = {1'b0, ir[7:0]};
+    wire signed [X-1:0] lui_0 = ((zext_w(rf[lui_rd]) & 66'sd255) | (lui_imm << 66'sd8));
+    wire signed [X-1:0] lui_1 = zext_w(lui_0[W-1:0]);
+
+    // AND rd, rs, rt: rd = rs & rt
+    wire is_and = (ir & 16'hf000) == 16'h5000;
+    wire [RB-1:0] and_rd = ir[11:8];
+    wire [RB-1:0] and_rs = ir[7:4];
+    wire [RB-1:0] and_rt = ir[3:0];
+    wire signed [X-1:0] and_0 = (zext_w(rf[and_rs]) & zext_w(rf[and_rt]));
+    wire signed [X-1:0] and_1 = zext_w(and_0[W-1:0]);
+
+    // SRA rd, rs, sh: rd = sext(rs) >> sh
+    wire is_sra = (ir & 16'hf000) == 16'h6000;
+    wire [RB-1:0] sra_rd = ir[11:8];
+    wire [RB-1:0] sra_rs = ir[7:4];
+    wire signed [X-1:0] sra_sh = {1'b0, ir[3:0]};
+    wire signed [X-1:0] sra_0 = (sext_w(zext_w(rf[sra_rs])) >>> sra_sh);
+    wire signed [X-1:0] sra_1 = zext_w(sra_0[W-1:0]);
+
+    // LD rd, rs, off: rd = mem[rs + off]
+    wire is_ld = (ir & 16'hf000) == 16'h7000;
+    wire [RB-1:0] ld_rd = ir[11:8];
+    wire [RB-1:0] ld_rs = ir[7:4];
+    wire signed [X-1:0] ld_off = $signed(ir[3:0]);
+    wire signed [X-1:0] ld_0 = (zext_w(rf[ld_rs]) + ld_off);
+    wire signed [X-1:0] ld_1 = zext_w(dmem_rdata);
+    wire signed [X-1:0] ld_2 = zext_w(ld_1[W-1:0]);
+
+    // ST rt, rs, off: mem[rs + off] = rt
+    wire is_st = (ir & 16'hf000) == 16'h8000;
+    wire [RB-1:0] st_rt = ir[11:8];
+    wire [RB-1:0] st_rs = ir[7:4];
+    wire signed [X-1:0] st_off = $signed(ir[3:0]);
+    wire signed [X-1:0] st_0 = (zext_w(rf[st_rs]) + st_off);
+    wire signed [X-1:0] st_1 = zext_w(rf[st_rt]);
+    wire signed [X-1:0] st_2 = zext_w(st_1[W-1:0]);
+
+    // BZ rs, off: if rs == 0 then pc = pc + off
+    wire is_bz = (ir & 16'hf000) == 16'h9000;
+    wire [RB-1:0] bz_rs = ir[11:8];
+    wire signed [X-1:0] bz_off = $signed(ir[7:0]);
+    wire bz_0 = |bit_x(zext_w(rf[bz_rs]) == 66'sd0);
+    wire signed [X-1:0] bz_1 = (zext_w(pc) + bz_off);
+    wire signed [X-1:0] bz_2 = bz_0 ? zext_w(bz_1[W-1:0]) : zext_w(pc);
+
+    // SLT rd, rs, rt: rd = sext(rs) < sext(rt)
+    wire is_slt = (ir & 16'hf000) == 16'ha000;
+    wire [RB-1:0] slt_rd = ir[11:8];
+    wire [RB-1:0] slt_rs = ir[7:4];
+    wire [RB-1:0] slt_rt = ir[3:0];
+    wire signed [X-1:0] slt_0 = bit_x(sext_w(zext_w(rf[slt_rs])) < sext_w(zext_w(rf[slt_rt])));
+    wire signed [X-1:0] slt_1 = zext_w(slt_0[W-1:0]);
+
+    // SWAP ra, rb: t = ra; ra = rb; rb = t
+    wire is_swap = (ir & 16'hf00f) == 16'hb000;
+    wire [RB-1:0] swap_ra = ir[11:8];
+    wire [RB-1:0] swap_rb = ir[7:4];
+    wire signed [X-1:0] swap_0 = zext_w(rf[swap_ra]);
+    wire signed [X-1:0] swap_1 = zext_w(rf[swap_rb]);
+    wire signed [X-1:0] swap_2 = zext_w(swap_1[W-1:0]);
+    wire signed [X-1:0] swap_3 = zext_w(swap_0[W-1:0]);
+
+    reg [W-1:0] pc_next;
+    reg flag_z_next, flag_c_next, flag_n_next, flag_v_next;
+    reg rf_we0;
+    reg [RB-1:0] rf_wa0;
+    reg [W-1:0] rf_wd0;
+    reg rf_we1;
+    reg [RB-1:0] rf_wa1;
+    reg [W-1:0] rf_wd1;
+
+    always @* begin
+        pc_next = pc + 1'b1;
+        {flag_z_next, flag_c_next, flag_n_next, flag_v_next} = {flag_z, flag_c, flag_n, flag_v};
+        rf_we0 = 1'b0;
+        rf_wa0 = 0;
+        rf_wd0 = 0;
+        rf_we1 = 1'b0;
+        rf_wa1 = 0;
+        rf_wd1 = 0;
+        dmem_addr = 0;
+        dmem_we = 1'b0;
+        dmem_wdata = 0;
+        illegal = ~(is_add | is_sub | is_addi | is_li | is_lui | is_and | is_sra | is_ld | is_st | is_bz | is_slt | is_swap);
+        if (is_add) begin
+            rf_we0 = 1'b1;
+            rf_wa0 = add_rd;
+            rf_wd0 = add_2[W-1:0];
+            flag_z_next = add_0[W-1:0] == 0;
+            flag_c_next = |add_0[X-1:W];
+            flag_n_next = add_0[W-1];
+            flag_v_next = ~(&add_1[X-1:W-1] | ~|add_1[X-1:W-1]);
+        end
+        if (is_sub) begin
+            rf_we0 = 1'b1;
+            rf_wa0 = sub_rd;
+            rf_wd0 = sub_1[W-1:0];
+            flag_z_next = sub_0[W-1:0] == 0;
+            flag_c_next = |sub_0[X-1:W];
+            flag_n_next = sub_0[W-1];
+        end
+        if (is_addi) begin
+            rf_we0 = 1'b1;
+            rf_wa0 = addi_rd;
+            rf_wd0 = addi_1[W-1:0];
+            flag_z_next = addi_0[W-1:0] == 0;
+            flag_c_next = |addi_0[X-1:W];
+            flag_n_next = addi_0[W-1];
+        end
+        if (is_li) begin
+            rf_we0 = 1'b1;
+            rf_wa0 = li_rd;
+            rf_wd0 = li_0[W-1:0];
+        end
+        if (is_lui) begin
+            rf_we0 = 1'b1;
+            rf_wa0 = lui_rd;
+            rf_wd0 = lui_1[W-1:0];
+        end
+        if (is_and) begin
+            rf_we0 = 1'b1;
+            rf_wa0 = and_rd;
+            rf_wd0 = and_1[W-1:0];
+            flag_z_next = and_0[W-1:0] == 0;
+            flag_n_next = and_0[W-1];
+        end
+        if (is_sra) begin
+            rf_we0 = 1'b1;
+            rf_wa0 = sra_rd;
+            rf_wd0 = sra_1[W-1:0];
+            flag_z_next = sra_0[W-1:0] == 0;
+            flag_n_next = sra_0[W-1];
+        end
+        if (is_ld) begin
+            rf_we0 = 1'b1;
+            rf_wa0 = ld_rd;
+            rf_wd0 = ld_2[W-1:0];
+            dmem_addr = ld_0[W-1:0];
+        end
+        if (is_st) begin
+            dmem_addr = st_0[W-1:0];
+            dmem_we = 1'b1;
+            dmem_wdata = st_2[W-1:0];
+        end
+        if (is_bz) begin
+            if (bz_0) pc_next = bz_2[W-1:0];
+        end
+        if (is_slt) begin
+            rf_we0 = 1'b1;
+            rf_wa0 = slt_rd;
+            rf_wd0 = slt_1[W-1:0];
+        end
+        if (is_swap) begin
+            rf_we0 = 1'b1;
+            rf_wa0 = swap_ra;
+            rf_wd0 = swap_2[W-1:0];
+            rf_we1 = 1'b1;
+            rf_wa1 = swap_rb;
+            rf_wd1 = swap_3[W-1:0];
+        end
+    end
+
+    always @(posedge clk) begin
+        if (rst) begin
+            pc <= 0;
+            {flag_z, flag_c, flag_n, flag_v} <= 4'b0;
+        end else begin
+            pc <= pc_next;
+            {flag_z, flag_c, flag_n, flag_v} <= {flag_z_next, flag_c_next, flag_n_next, flag_v_next};
+            if (rf_we0) rf[rf_wa0] <= rf_wd0;
+            if (rf_we1) rf[rf_wa1] <= rf_wd1;
+        end
+    end
+endmodule
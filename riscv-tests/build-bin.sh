@@ -0,0 +1,15 @@
+#!/bin/sh
+# Rebuilds bin/ from the .S sources with LLVM's integrated assembler, which
+# shares no code with the simulator's own assembler. The tests keep their
+# data in .text, so with relaxation off every reference is resolved at
+# assembly time and no linker is needed.
+set -e
+cd "$(dirname "$0")"
+mkdir -p bin
+for src in rv*.S; do
+    name=${src%.S}
+    xlen=${name%%u*}
+    llvm-mc -triple="riscv${xlen#rv}" -mattr=+m,-relax -filetype=obj -o "bin/$name.o" "$src"
+    llvm-objcopy -O binary -j .text "bin/$name.o" "bin/$name.bin"
+    rm "bin/$name.o"
+done